@@ -6,11 +6,15 @@
 
 use metrics::Metric;
 use reporter::Reporter;
+use reporter::ReporterHandle;
+use reporter::Unit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::thread;
 
 pub struct ConsoleReporter {
-    metrics: Vec<Metric>,
+    metrics: Vec<(Metric, Unit)>,
     reporter_name: &'static str,
 }
 
@@ -29,31 +33,40 @@ impl ConsoleReporter {
     }
 
     pub fn add(&mut self, metric: Metric) {
-        self.metrics.push(metric);
+        self.add_with_unit(metric, Unit::Count);
     }
 
-    pub fn start(self, delay_ms: u64) {
-        thread::spawn(move || {
-            loop {
-                for metric in &self.metrics {
+    // Register a metric together with the `Unit` it is measured in, which is
+    // echoed alongside each dump so console output is self-describing.
+    pub fn add_with_unit(&mut self, metric: Metric, unit: Unit) {
+        self.metrics.push((metric, unit));
+    }
+
+    pub fn start(self, delay_ms: u64) -> ReporterHandle {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let handle = thread::spawn(move || {
+            while thread_running.load(Ordering::SeqCst) {
+                for &(ref metric, unit) in &self.metrics {
                     match *metric {
                         Metric::Meter(ref x) => {
-                            println!("{:?}", x.snapshot());
+                            println!("[{:?}] {:?}", unit, x.snapshot());
                         }
                         Metric::Gauge(ref x) => {
-                            println!("{:?}", x.snapshot());
+                            println!("[{:?}] {:?}", unit, x.snapshot());
                         }
                         Metric::Counter(ref x) => {
-                            println!("{:?}", x.snapshot());
+                            println!("[{:?}] {:?}", unit, x.snapshot());
                         }
                         Metric::Histogram(ref x) => {
-                            println!("histogram{:?}", x);
+                            println!("[{:?}] histogram{:?}", unit, x);
                         }
                     }
                 }
                 thread::sleep(Duration::from_millis(delay_ms));
             }
         });
+        ReporterHandle::new(running, handle)
     }
 }
 
@@ -90,7 +103,7 @@ mod test {
         reporter.add(Metric::Counter(c.clone()));
         reporter.add(Metric::Gauge(g.clone()));
         reporter.add(Metric::Histogram(h));
-        reporter.start(1);
+        let _handle = reporter.start(1);
         g.set(4);
         thread::sleep(Duration::from_millis(200));
         println!("poplopit");
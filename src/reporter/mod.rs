@@ -0,0 +1,58 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+pub mod console;
+pub mod prometheus;
+pub mod stream;
+pub mod text;
+pub mod unit;
+
+pub use self::unit::Unit;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+// Shared behaviour every reporter exposes so they can be held behind a common
+// handle regardless of where they send their metrics.
+pub trait Reporter: Send {
+    fn get_unique_reporter_name(&self) -> &'static str;
+}
+
+// Handle to a running reporter thread. Holds the spawned thread's join handle
+// together with the shared flag its loop polls each iteration, so callers can
+// shut the thread down deterministically instead of leaking it. Dropping the
+// handle stops the thread automatically.
+pub struct ReporterHandle {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ReporterHandle {
+    // Build a handle over a freshly spawned reporter thread and the flag it
+    // shares with that thread's loop.
+    pub fn new(running: Arc<AtomicBool>, handle: JoinHandle<()>) -> Self {
+        ReporterHandle {
+            running: running,
+            handle: Some(handle),
+        }
+    }
+
+    // Signal the reporter loop to stop and wait for the thread to finish.
+    // Idempotent: a second call is a no-op once the thread has been joined.
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ReporterHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -15,8 +15,15 @@ use self::prometheus_reporter::promo_proto;
 use std::time::Duration;
 use std::thread;
 use metrics::Metric;
+use histogram::Histogram;
+use reporter::Reporter;
+use reporter::Unit;
+use reporter::ReporterHandle;
+use reporter::unit::metric_name;
 use time;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use self::protobuf::repeated::RepeatedField;
 
@@ -24,6 +31,41 @@ struct PrometheusMetricEntry {
     name: &'static str,
     metric: Metric,
     labels: HashMap<String, String>,
+    unit: Unit,
+    exemplar: Option<Exemplar>,
+}
+
+// Combined length budget for an exemplar's label names and values, mirroring
+// the OpenMetrics guidance that exemplar label sets stay small (they usually
+// carry only a trace id).
+const MAX_EXEMPLAR_LABELS_LEN: usize = 128;
+
+// An OpenMetrics exemplar: a handful of label pairs (typically a trace id)
+// attached to a single counter increment or histogram observation.
+//
+// LIMITATION: the legacy `promo_proto` model this crate builds against predates
+// the OpenMetrics exemplar field, so there is nowhere on the wire to put an
+// exemplar. Exemplars are validated for length and then dropped at emission
+// (see `make_metric`); they are not folded onto the sample's label set, because
+// a per-observation trace id would create one series per trace and explode
+// cardinality. This type exists so the API is ready for a client model that
+// does support exemplars.
+pub struct Exemplar {
+    labels: HashMap<String, String>,
+}
+
+impl Exemplar {
+    // Build an exemplar from its label pairs, rejecting label sets whose
+    // combined name/value length exceeds `MAX_EXEMPLAR_LABELS_LEN`.
+    pub fn new(labels: HashMap<String, String>) -> Result<Self, String> {
+        let len: usize = labels.iter().map(|(k, v)| k.len() + v.len()).sum();
+        if len > MAX_EXEMPLAR_LABELS_LEN {
+            return Err(format!("Exemplar labels too long: {} > {}",
+                               len,
+                               MAX_EXEMPLAR_LABELS_LEN));
+        }
+        Ok(Exemplar { labels: labels })
+    }
 }
 
 // TODO perhaps we autodiscover the host and port
@@ -31,31 +73,117 @@ struct PrometheusMetricEntry {
 pub struct PrometheusReporter {
     reporter_name: &'static str,
     host_and_port: &'static str,
+    namespace: &'static str,
+    bucket_bounds: Vec<f64>,
     tx: Option<mpsc::Sender<PrometheusMetricEntry>>,
 }
 
+impl Reporter for PrometheusReporter {
+    fn get_unique_reporter_name(&self) -> &'static str {
+        self.reporter_name
+    }
+}
+
+// Application namespace prepended to every metric name. Prometheus encourages
+// a single-word prefix identifying the emitting application.
+const DEFAULT_NAMESPACE: &'static str = "application";
+
+// Default histogram bucket upper bounds: an exponential ladder of 16 powers of
+// two from 1 to 32768, which is a reasonable starting point for most latency
+// and size distributions.
+fn default_bucket_bounds() -> Vec<f64> {
+    exponential_bounds(1.0, 2.0, 16)
+}
+
+// Build `count` exponentially spaced upper bounds starting at `start` and
+// growing by `factor` each step.
+fn exponential_bounds(start: f64, factor: f64, count: usize) -> Vec<f64> {
+    let mut bounds = Vec::with_capacity(count);
+    let mut next = start;
+    for _ in 0..count {
+        bounds.push(next);
+        next *= factor;
+    }
+    bounds
+}
+
 impl PrometheusReporter {
     pub fn new(reporter_name: &'static str, host_and_port: &'static str) -> Self {
         PrometheusReporter {
             reporter_name: reporter_name,
             host_and_port: host_and_port,
+            namespace: DEFAULT_NAMESPACE,
+            bucket_bounds: default_bucket_bounds(),
             tx: None,
         }
     }
 
+    // Override the application namespace prefix applied to every metric name.
+    pub fn with_namespace(mut self, namespace: &'static str) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    // Override the histogram bucket upper bounds (`le` values) used when
+    // encoding `Metric::Histogram` samples. The mandatory `+Inf` bucket is
+    // always appended automatically, so it should not be included here.
+    pub fn with_bucket_bounds(mut self, bucket_bounds: Vec<f64>) -> Self {
+        self.bucket_bounds = bucket_bounds;
+        self
+    }
+
     pub fn add(&mut self,
                name: &'static str,
                metric: Metric,
                labels: HashMap<String, String>)
                -> Result<(), String> {
+        self.add_with_unit(name, metric, labels, Unit::Count)
+    }
+
+    // Register a metric together with the `Unit` it is measured in, which
+    // drives the Prometheus name suffix and any value normalization.
+    pub fn add_with_unit(&mut self,
+                         name: &'static str,
+                         metric: Metric,
+                         labels: HashMap<String, String>,
+                         unit: Unit)
+                         -> Result<(), String> {
+        self.send(PrometheusMetricEntry {
+            name: name,
+            metric: metric,
+            labels: labels,
+            unit: unit,
+            exemplar: None,
+        })
+    }
+
+    // Register a counter increment or histogram observation together with an
+    // OpenMetrics exemplar built from `exemplar_labels` (typically a trace id).
+    // The exemplar labels are validated for length here, but NOTE they are
+    // currently dropped at emission: the legacy `promo_proto` model has no
+    // exemplar field. See the `Exemplar` docs for why they are not folded onto
+    // the series labels instead.
+    pub fn add_with_exemplar(&mut self,
+                             name: &'static str,
+                             metric: Metric,
+                             labels: HashMap<String, String>,
+                             exemplar_labels: HashMap<String, String>)
+                             -> Result<(), String> {
+        let exemplar = try!(Exemplar::new(exemplar_labels));
+        self.send(PrometheusMetricEntry {
+            name: name,
+            metric: metric,
+            labels: labels,
+            unit: Unit::Count,
+            exemplar: Some(exemplar),
+        })
+    }
+
+    fn send(&mut self, entry: PrometheusMetricEntry) -> Result<(), String> {
         // TODO return error
         match self.tx {
             Some(ref mut tx) => {
-                match tx.send(PrometheusMetricEntry {
-                    name: name,
-                    metric: metric,
-                    labels: labels,
-                }) {
+                match tx.send(entry) {
                     Ok(x) => Ok(x),
                     Err(y) => Err(format!("Unable to send {}", y)),
                 }
@@ -64,18 +192,26 @@ impl PrometheusReporter {
         }
     }
 
-    pub fn start(&mut self, delay_ms: u64) {
+    pub fn start(&mut self, delay_ms: u64) -> ReporterHandle {
         let (tx, rx) = mpsc::channel();
         self.tx = Some(tx);
         let host_and_port = self.host_and_port.clone();
-        thread::spawn(move || {
+        let namespace = self.namespace.clone();
+        let bucket_bounds = self.bucket_bounds.clone();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let handle = thread::spawn(move || {
             let mut prometheus_reporter = Pr::new(host_and_port);
             prometheus_reporter.start().unwrap();
-            loop {
-                prometheus_reporter.add(collect_to_send(&rx));
+            while thread_running.load(Ordering::SeqCst) {
+                prometheus_reporter.add(collect_to_send(&rx, namespace, &bucket_bounds));
                 thread::sleep(Duration::from_millis(delay_ms));
             }
+            // Flush any entries still queued in the receiver before exiting so a
+            // shutdown does not silently drop the last interval's metrics.
+            prometheus_reporter.add(collect_to_send(&rx, namespace, &bucket_bounds));
         });
+        ReporterHandle::new(running, handle)
     }
 }
 
@@ -93,7 +229,10 @@ fn to_repeated_fields_labels(labels: HashMap<String, String>)
 }
 
 fn make_metric(metric: &Metric,
-               labels: &HashMap<String, String>)
+               labels: &HashMap<String, String>,
+               unit: Unit,
+               bucket_bounds: &[f64],
+               exemplar: Option<&Exemplar>)
                -> (promo_proto::Metric, promo_proto::MetricType) {
 
     let mut pb_metric = promo_proto::Metric::new();
@@ -101,63 +240,152 @@ fn make_metric(metric: &Metric,
 
     pb_metric.set_timestamp_ms(ts);
     pb_metric.set_label(to_repeated_fields_labels(labels.clone()));
+    // The legacy `promo_proto` model has no exemplar field on counters or
+    // histogram buckets, so a recorded exemplar cannot be emitted. We must NOT
+    // merge its labels onto the sample's own label set either: a trace id
+    // varies per observation, so that would mint a fresh Prometheus series for
+    // every request and blow up cardinality. The exemplar is validated on the
+    // way in (see `add_with_exemplar`) and then deliberately dropped here until
+    // the client model gains native exemplar support.
+    if exemplar.is_some() {
+        // Intentionally ignored; see above.
+    }
     match *metric {
         Metric::Counter(ref x) => {
             let snapshot = x.snapshot();
             let mut counter = promo_proto::Counter::new();
-            counter.set_value(snapshot.value as f64);
+            counter.set_value(unit.normalize(snapshot.value as f64));
             pb_metric.set_counter(counter);
             (pb_metric, promo_proto::MetricType::COUNTER)
         }
         Metric::Gauge(ref x) => {
             let snapshot = x.snapshot();
             let mut gauge = promo_proto::Gauge::new();
-            gauge.set_value(snapshot.value as f64);
+            gauge.set_value(unit.normalize(snapshot.value as f64));
             pb_metric.set_gauge(gauge);
             (pb_metric, promo_proto::MetricType::GAUGE)
         }
-        Metric::Meter(_) => {
-            pb_metric.set_summary(promo_proto::Summary::new());
+        Metric::Meter(ref x) => {
+            let snapshot = x.snapshot();
+            let mut summary = promo_proto::Summary::new();
+            summary.set_sample_count(snapshot.count as u64);
+            summary.set_sample_sum(unit.normalize(snapshot.mean * snapshot.count as f64));
+            // NOTE: these are NOT real quantiles. A meter exposes one/five/
+            // fifteen minute rates, not a latency distribution, but the legacy
+            // `promo_proto` Summary has no field for rates, so we park the three
+            // rates under the `quantile` labels 0.5/0.9/0.99 purely so scrapers
+            // that understand summaries still surface a usable signal. A reader
+            // of `quantile="0.99"` is seeing the fifteen minute rate, not a p99;
+            // treat these as rate-carrying placeholders, not percentiles.
+            let mut quantiles = Vec::new();
+            for &(quantile, rate) in &[(0.5, snapshot.rates[0]),
+                                       (0.9, snapshot.rates[1]),
+                                       (0.99, snapshot.rates[2])] {
+                let mut pb_quantile = promo_proto::Quantile::new();
+                pb_quantile.set_quantile(quantile);
+                pb_quantile.set_value(rate);
+                quantiles.push(pb_quantile);
+            }
+            summary.set_quantile(RepeatedField::from_vec(quantiles));
+            pb_metric.set_summary(summary);
             (pb_metric, promo_proto::MetricType::SUMMARY)
-
         }
-        Metric::Histogram(_) => {
-            pb_metric.set_histogram(promo_proto::Histogram::new());
+        Metric::Histogram(ref x) => {
+            // Any exemplar was dropped above rather than merged onto
+            // `pb_metric`'s labels, so the histogram only carries the buckets.
+            pb_metric.set_histogram(make_histogram(x, unit, bucket_bounds));
             (pb_metric, promo_proto::MetricType::HISTOGRAM)
         }
     }
 }
 
-fn collect_to_send(metric_entries: &mpsc::Receiver<PrometheusMetricEntry>)
+// Encode a recorded histogram as cumulative Prometheus buckets over the
+// configured `le` bounds, always closed off by the mandatory `+Inf` bucket.
+fn make_histogram(histogram: &Histogram,
+                  unit: Unit,
+                  bucket_bounds: &[f64])
+                  -> promo_proto::Histogram {
+    let mut pb_histogram = promo_proto::Histogram::new();
+    let total = histogram.entries();
+
+    let mut buckets = Vec::with_capacity(bucket_bounds.len() + 1);
+    let mut sample_sum = 0f64;
+    for bucket in histogram {
+        sample_sum += unit.normalize(bucket.value() as f64) * bucket.count() as f64;
+    }
+
+    // An empty histogram collapses to a single `+Inf` bucket with count 0.
+    if total > 0 {
+        for &le in bucket_bounds {
+            let mut count = 0u64;
+            for bucket in histogram {
+                if unit.normalize(bucket.value() as f64) <= le {
+                    count += bucket.count();
+                }
+            }
+            let mut pb_bucket = promo_proto::Bucket::new();
+            pb_bucket.set_cumulative_count(count);
+            pb_bucket.set_upper_bound(le);
+            buckets.push(pb_bucket);
+        }
+    }
+
+    let mut inf_bucket = promo_proto::Bucket::new();
+    inf_bucket.set_cumulative_count(total);
+    inf_bucket.set_upper_bound(f64::INFINITY);
+    buckets.push(inf_bucket);
+
+    pb_histogram.set_bucket(RepeatedField::from_vec(buckets));
+    pb_histogram.set_sample_count(total);
+    pb_histogram.set_sample_sum(sample_sum);
+    pb_histogram
+}
+
+fn collect_to_send(metric_entries: &mpsc::Receiver<PrometheusMetricEntry>,
+                   namespace: &'static str,
+                   bucket_bounds: &[f64])
                    -> Vec<promo_proto::MetricFamily> {
-    let mut entries_group = HashMap::<&'static str, Vec<PrometheusMetricEntry>>::new();
+    // A family must be type-homogeneous and share a name, so group by the final
+    // formatted name *and* metric type. Keying on the formatted name (rather
+    // than the raw name plus unit) keeps units that collapse onto the same
+    // suffix — e.g. `Seconds` and `Milliseconds` both emit `_seconds` — in a
+    // single family instead of two families with identical names. Each entry is
+    // still normalized by its own unit, so millisecond values become seconds.
+    // Entries within a family keep their own distinct label pairs, which is how
+    // Prometheus models dimensioned series (`name{dimension_1=...}`).
+    let mut entries_group =
+        HashMap::<(String, promo_proto::MetricType), Vec<PrometheusMetricEntry>>::new();
 
-    // Group them by name TODO we should include tags and types in the grouping
-    for entry in metric_entries {
-        let name = entry.name;
-        let mut entries = entries_group.remove(name).unwrap_or(vec![]);
+    // Drain only what is currently queued; `try_iter` returns once the channel
+    // is momentarily empty instead of blocking in `recv` until every `Sender`
+    // is dropped. `self.tx` keeps a sender alive for the reporter's whole life,
+    // so a blocking iterator would never return and the post-loop shutdown
+    // flush (plus `ReporterHandle::stop`/`Drop`'s join) would hang forever.
+    for entry in metric_entries.try_iter() {
+        let key = (metric_name(namespace, entry.name, entry.unit),
+                   metric_type(&entry.metric));
+        let mut entries = entries_group.remove(&key).unwrap_or(vec![]);
         entries.push(entry);
-        entries_group.insert(name, entries);
+        entries_group.insert(key, entries);
     }
 
     let mut families = Vec::new();
-    for (name, metric_entries) in &entries_group {
-        let formatted_metric = format!("{}_{}_{}", "application_name", name, "bytes");
+    for ((formatted_metric, pb_metric_type), metric_entries) in entries_group {
         // TODO check for 0 length
 
-        let ref e1: PrometheusMetricEntry = metric_entries[0];
-        let (_, pb_metric_type) = make_metric(&e1.metric, &e1.labels);
-
         let mut family = promo_proto::MetricFamily::new();
         let mut pb_metrics = Vec::new();
 
-        for metric_entry in metric_entries {
-            // TODO maybe don't assume they have the same type
-            let (pb_metric, _) = make_metric(&metric_entry.metric, &metric_entry.labels);
+        for metric_entry in &metric_entries {
+            let (pb_metric, _) = make_metric(&metric_entry.metric,
+                                             &metric_entry.labels,
+                                             metric_entry.unit,
+                                             bucket_bounds,
+                                             metric_entry.exemplar.as_ref());
             pb_metrics.push(pb_metric);
         }
 
-        family.set_name(String::from(formatted_metric));
+        family.set_name(formatted_metric);
         family.set_field_type(pb_metric_type);
         family.set_metric(RepeatedField::from_vec(pb_metrics));
         families.push(family);
@@ -165,6 +393,17 @@ fn collect_to_send(metric_entries: &mpsc::Receiver<PrometheusMetricEntry>)
     families
 }
 
+// The Prometheus metric type a `Metric` encodes to. Kept in sync with
+// `make_metric` so families can be grouped without building the protobuf.
+fn metric_type(metric: &Metric) -> promo_proto::MetricType {
+    match *metric {
+        Metric::Counter(_) => promo_proto::MetricType::COUNTER,
+        Metric::Gauge(_) => promo_proto::MetricType::GAUGE,
+        Metric::Meter(_) => promo_proto::MetricType::SUMMARY,
+        Metric::Histogram(_) => promo_proto::MetricType::HISTOGRAM,
+    }
+}
+
 
 
 #[cfg(test)]
@@ -194,7 +433,7 @@ mod test {
         h.increment_by(1, 1).unwrap();
 
         let mut reporter = PrometheusReporter::new("test", "0.0.0.0:80");
-        reporter.start(1024);
+        let _handle = reporter.start(1024);
         let labels = HashMap::new();
         reporter.add("meter1", Metric::Meter(m.clone()), labels.clone());
         reporter.add("counter1", Metric::Counter(c.clone()), labels.clone());
@@ -0,0 +1,269 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// StreamReporter batches the registered metrics every interval and pushes them
+// to a remote collector over a persistent streaming channel, mirroring the
+// batch-and-send loop used by the PrometheusReporter but targeting environments
+// that collect telemetry over a stream rather than a scrape or a protobuf push
+// gateway.
+//
+// This is the transport-agnostic half of a gRPC push reporter. The crate does
+// not bundle a gRPC client (that would pull a heavy codegen/transport
+// dependency into a small metrics crate), so the streaming channel itself is a
+// `CollectorSink` the caller supplies — typically a thin wrapper over a tonic
+// or grpc-rust client generated from their collector's service definition. That
+// is why `start` takes the sink as an argument rather than constructing one
+// from an endpoint string: the reporter owns the batching cadence and the wire
+// encoding (`MetricSample`), the caller owns the transport and its connection.
+
+use metrics::Metric;
+use reporter::Reporter;
+use reporter::ReporterHandle;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// Default histogram bucket upper bounds used when a histogram is pushed
+// without explicit bounds configured, matching the exponential ladder the
+// PrometheusReporter defaults to.
+fn default_bucket_bounds() -> Vec<f64> {
+    let mut bounds = Vec::with_capacity(16);
+    let mut next = 1.0;
+    for _ in 0..16 {
+        bounds.push(next);
+        next *= 2.0;
+    }
+    bounds
+}
+
+struct StreamMetricEntry {
+    name: &'static str,
+    metric: Metric,
+    labels: HashMap<String, String>,
+}
+
+// A single metric converted into the wire form streamed to the collector.
+// Counters carry their monotonic value, gauges their instantaneous value,
+// histograms their cumulative buckets plus count/sum, and meters their
+// snapshot rates.
+pub enum MetricSample {
+    Counter {
+        name: &'static str,
+        labels: HashMap<String, String>,
+        value: f64,
+    },
+    Gauge {
+        name: &'static str,
+        labels: HashMap<String, String>,
+        value: f64,
+    },
+    Histogram {
+        name: &'static str,
+        labels: HashMap<String, String>,
+        buckets: Vec<(f64, u64)>,
+        sample_count: u64,
+        sample_sum: f64,
+    },
+    Meter {
+        name: &'static str,
+        labels: HashMap<String, String>,
+        sample_count: u64,
+        rates: [f64; 3],
+        mean: f64,
+    },
+}
+
+// The streaming transport the reporter flushes each batch over, implemented by
+// the caller against their own gRPC client. Abstracted so the batching loop
+// stays independent of the concrete client and can be exercised against a fake
+// in tests.
+pub trait CollectorSink: Send {
+    // Open (or re-open) the streaming channel to the collector.
+    fn connect(&mut self) -> Result<(), String>;
+    // Push one interval's worth of samples down the stream.
+    fn push(&mut self, batch: &[MetricSample]) -> Result<(), String>;
+}
+
+pub struct StreamReporter {
+    reporter_name: &'static str,
+    bucket_bounds: Vec<f64>,
+    tx: Option<mpsc::Sender<StreamMetricEntry>>,
+}
+
+impl Reporter for StreamReporter {
+    fn get_unique_reporter_name(&self) -> &'static str {
+        self.reporter_name
+    }
+}
+
+impl StreamReporter {
+    pub fn new(reporter_name: &'static str) -> Self {
+        StreamReporter {
+            reporter_name: reporter_name,
+            bucket_bounds: default_bucket_bounds(),
+            tx: None,
+        }
+    }
+
+    // Override the histogram bucket upper bounds streamed to the collector.
+    pub fn with_bucket_bounds(mut self, bucket_bounds: Vec<f64>) -> Self {
+        self.bucket_bounds = bucket_bounds;
+        self
+    }
+
+    pub fn add(&mut self,
+               name: &'static str,
+               metric: Metric,
+               labels: HashMap<String, String>)
+               -> Result<(), String> {
+        // TODO return error
+        match self.tx {
+            Some(ref mut tx) => {
+                let entry = StreamMetricEntry {
+                    name: name,
+                    metric: metric,
+                    labels: labels,
+                };
+                match tx.send(entry) {
+                    Ok(x) => Ok(x),
+                    Err(y) => Err(format!("Unable to send {}", y)),
+                }
+            }
+            None => Err(format!("Please start the reporter before trying to add to it")),
+        }
+    }
+
+    // Start streaming batches to `sink` every `delay_ms`. Returns a handle that
+    // stops and joins the streaming thread when dropped, like the other
+    // reporters, so it does not leak a thread.
+    pub fn start<S>(&mut self, mut sink: S, delay_ms: u64) -> ReporterHandle
+        where S: CollectorSink + 'static
+    {
+        let (tx, rx) = mpsc::channel();
+        self.tx = Some(tx);
+        let bucket_bounds = self.bucket_bounds.clone();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let handle = thread::spawn(move || {
+            // Transport errors must not tear the reporter down: a single failed
+            // `connect`/`push` used to `unwrap()` and panic the thread, silently
+            // ending all metric delivery for the process' life. Instead we track
+            // whether the stream is up and try to (re)connect each interval,
+            // logging failures and retrying on the next tick. Entries keep
+            // queueing on `rx` while we are disconnected and are drained once the
+            // stream comes back.
+            let mut connected = connect(&mut sink);
+            while thread_running.load(Ordering::SeqCst) {
+                if !connected {
+                    connected = connect(&mut sink);
+                }
+                if connected {
+                    let batch = collect_batch(&rx, &bucket_bounds);
+                    if !batch.is_empty() {
+                        if let Err(e) = sink.push(&batch) {
+                            println!("StreamReporter: push failed, will reconnect: {}", e);
+                            connected = false;
+                        }
+                    }
+                }
+                thread::sleep(Duration::from_millis(delay_ms));
+            }
+            // Flush any entries still queued before the thread exits so a
+            // shutdown does not silently drop the last interval's metrics.
+            // If the stream is down at that point the queued entries are lost.
+            if connected {
+                let batch = collect_batch(&rx, &bucket_bounds);
+                if !batch.is_empty() {
+                    let _ = sink.push(&batch);
+                }
+            }
+        });
+        ReporterHandle::new(running, handle)
+    }
+}
+
+// Try to open the stream, logging and swallowing any error so the caller can
+// retry on the next interval instead of panicking.
+fn connect<S: CollectorSink>(sink: &mut S) -> bool {
+    match sink.connect() {
+        Ok(()) => true,
+        Err(e) => {
+            println!("StreamReporter: connect failed, will retry: {}", e);
+            false
+        }
+    }
+}
+
+// Drain every entry currently queued on the receiver into a batch of wire
+// samples. Mirrors `collect_to_send` in the prometheus module: the channel is
+// read until it is momentarily empty, leaving the interval sleep to the caller.
+fn collect_batch(metric_entries: &mpsc::Receiver<StreamMetricEntry>,
+                 bucket_bounds: &[f64])
+                 -> Vec<MetricSample> {
+    let mut batch = Vec::new();
+    for entry in metric_entries.try_iter() {
+        batch.push(make_sample(entry, bucket_bounds));
+    }
+    batch
+}
+
+fn make_sample(entry: StreamMetricEntry, bucket_bounds: &[f64]) -> MetricSample {
+    let StreamMetricEntry { name, metric, labels } = entry;
+    match metric {
+        Metric::Counter(ref x) => {
+            MetricSample::Counter {
+                name: name,
+                labels: labels,
+                value: x.snapshot().value as f64,
+            }
+        }
+        Metric::Gauge(ref x) => {
+            MetricSample::Gauge {
+                name: name,
+                labels: labels,
+                value: x.snapshot().value as f64,
+            }
+        }
+        Metric::Meter(ref x) => {
+            let snapshot = x.snapshot();
+            MetricSample::Meter {
+                name: name,
+                labels: labels,
+                sample_count: snapshot.count as u64,
+                rates: snapshot.rates,
+                mean: snapshot.mean,
+            }
+        }
+        Metric::Histogram(ref x) => {
+            let total = x.entries();
+            let mut buckets = Vec::with_capacity(bucket_bounds.len() + 1);
+            let mut sample_sum = 0f64;
+            for bucket in x {
+                sample_sum += bucket.value() as f64 * bucket.count() as f64;
+            }
+            for &le in bucket_bounds {
+                let mut count = 0u64;
+                for bucket in x {
+                    if bucket.value() as f64 <= le {
+                        count += bucket.count();
+                    }
+                }
+                buckets.push((le, count));
+            }
+            buckets.push((f64::INFINITY, total));
+            MetricSample::Histogram {
+                name: name,
+                labels: labels,
+                buckets: buckets,
+                sample_count: total,
+                sample_sum: sample_sum,
+            }
+        }
+    }
+}
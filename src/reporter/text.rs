@@ -0,0 +1,252 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// TextReporter exposes the registered metrics in the standard Prometheus /
+// OpenMetrics text exposition format over an HTTP endpoint. Unlike the
+// PrometheusReporter it does not push on a timer: the metric set is serialized
+// on demand each time a scrape hits the endpoint, so it fits scrape-based
+// Prometheus servers rather than push gateways.
+
+use metrics::Metric;
+use reporter::Reporter;
+use reporter::ReporterHandle;
+use reporter::Unit;
+use reporter::unit::metric_name;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Quantiles surfaced for a meter, mapped onto its one/five/fifteen minute
+// rates the same way the PrometheusReporter does when it builds a summary.
+const METER_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+struct TextMetricEntry {
+    name: &'static str,
+    metric: Metric,
+    labels: HashMap<String, String>,
+    unit: Unit,
+}
+
+pub struct TextReporter {
+    reporter_name: &'static str,
+    namespace: &'static str,
+    metrics: Arc<Mutex<Vec<TextMetricEntry>>>,
+}
+
+impl Reporter for TextReporter {
+    fn get_unique_reporter_name(&self) -> &'static str {
+        self.reporter_name
+    }
+}
+
+impl TextReporter {
+    pub fn new(reporter_name: &'static str) -> Self {
+        TextReporter {
+            reporter_name: reporter_name,
+            namespace: "application",
+            metrics: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    // Override the application namespace prefix applied to every metric name.
+    pub fn with_namespace(mut self, namespace: &'static str) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    pub fn add(&mut self,
+               name: &'static str,
+               metric: Metric,
+               labels: HashMap<String, String>)
+               -> Result<(), String> {
+        self.add_with_unit(name, metric, labels, Unit::Count)
+    }
+
+    // Register a metric together with the `Unit` it is measured in, which
+    // drives the name suffix used in the exposition output.
+    pub fn add_with_unit(&mut self,
+                         name: &'static str,
+                         metric: Metric,
+                         labels: HashMap<String, String>,
+                         unit: Unit)
+                         -> Result<(), String> {
+        let mut metrics = try!(self.metrics
+            .lock()
+            .map_err(|_| format!("Unable to register {}: reporter lock poisoned", name)));
+        metrics.push(TextMetricEntry {
+            name: name,
+            metric: metric,
+            labels: labels,
+            unit: unit,
+        });
+        Ok(())
+    }
+
+    // Serve the exposition format on `host_and_port`, re-serializing the
+    // current metric set for every scrape. Returns a handle that shuts the
+    // serving thread down when stopped or dropped.
+    pub fn start(&self, host_and_port: &'static str) -> ReporterHandle {
+        let metrics = self.metrics.clone();
+        let namespace = self.namespace.clone();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let handle = thread::spawn(move || {
+            let listener = TcpListener::bind(host_and_port).unwrap();
+            // Non-blocking accept so the loop can observe the stop flag between
+            // scrapes instead of parking in `accept()` until the next inbound
+            // connection (which might never arrive), which would make
+            // `ReporterHandle::stop`/`Drop` hang on `join`.
+            listener.set_nonblocking(true).unwrap();
+            while thread_running.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((mut stream, _)) => {
+                        let mut request = [0u8; 512];
+                        let _ = stream.read(&mut request);
+                        let body = render(&metrics, namespace);
+                        let response = format!("HTTP/1.1 200 OK\r\nContent-Type: \
+                                                text/plain; version=0.0.4\r\nContent-Length: \
+                                                {}\r\n\r\n{}",
+                                               body.len(),
+                                               body);
+                        let _ = stream.write_all(response.as_bytes());
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        ReporterHandle::new(running, handle)
+    }
+}
+
+// Render the whole metric set into the text exposition format, one `# HELP` /
+// `# TYPE` header block per metric family followed by the sample lines of
+// every registered metric in that family.
+fn render(metrics: &Arc<Mutex<Vec<TextMetricEntry>>>, namespace: &'static str) -> String {
+    let metrics = match metrics.lock() {
+        Ok(metrics) => metrics,
+        Err(_) => return String::new(),
+    };
+
+    // Group series by `(formatted name, type)` preserving first-seen order. The
+    // exposition format allows only one `# TYPE`/`# HELP` block per metric name,
+    // so entries that share a name but differ in labels (the dimensioned-series
+    // case) must appear under a single header followed by all their sample
+    // lines, not a repeated header each.
+    let mut families: Vec<(String, &'static str, Vec<&TextMetricEntry>)> = Vec::new();
+    for entry in metrics.iter() {
+        let name = metric_name(namespace, entry.name, entry.unit);
+        let metric_type = metric_type_str(&entry.metric);
+        let mut found = None;
+        for (i, &(ref n, t, _)) in families.iter().enumerate() {
+            if *n == name && t == metric_type {
+                found = Some(i);
+                break;
+            }
+        }
+        match found {
+            Some(i) => families[i].2.push(entry),
+            None => families.push((name, metric_type, vec![entry])),
+        }
+    }
+
+    let mut out = String::new();
+    for &(ref name, metric_type, ref entries) in &families {
+        header(&mut out, name, metric_type);
+        for entry in entries {
+            render_samples(&mut out, name, entry);
+        }
+    }
+    out
+}
+
+// The exposition type keyword a `Metric` renders as.
+fn metric_type_str(metric: &Metric) -> &'static str {
+    match *metric {
+        Metric::Counter(_) => "counter",
+        Metric::Gauge(_) => "gauge",
+        Metric::Meter(_) => "summary",
+        Metric::Histogram(_) => "histogram",
+    }
+}
+
+// Emit only the sample lines for one series; the family's header is written
+// once by the caller.
+fn render_samples(out: &mut String, name: &str, entry: &TextMetricEntry) {
+    let unit = entry.unit;
+    match entry.metric {
+        Metric::Counter(ref x) => {
+            line(out, name, &entry.labels, unit.normalize(x.snapshot().value as f64));
+        }
+        Metric::Gauge(ref x) => {
+            line(out, name, &entry.labels, unit.normalize(x.snapshot().value as f64));
+        }
+        Metric::Meter(ref x) => {
+            let snapshot = x.snapshot();
+            for (i, &quantile) in METER_QUANTILES.iter().enumerate() {
+                let mut labels = entry.labels.clone();
+                labels.insert(String::from("quantile"), format!("{}", quantile));
+                line(out, name, &labels, snapshot.rates[i]);
+            }
+            line(out, &format!("{}_sum", name), &entry.labels,
+                 unit.normalize(snapshot.mean * snapshot.count as f64));
+            line(out, &format!("{}_count", name), &entry.labels, snapshot.count as f64);
+        }
+        Metric::Histogram(ref x) => {
+            let total = x.entries();
+            let mut sample_sum = 0f64;
+            for bucket in x {
+                sample_sum += unit.normalize(bucket.value() as f64) * bucket.count() as f64;
+            }
+            // The histogram exposes its own recorded bucket boundaries, which
+            // we surface directly as cumulative `le` series, normalized to the
+            // unit's base so the `le` bounds match the `_sum`/`_count` values.
+            let mut cumulative = 0u64;
+            for bucket in x {
+                cumulative += bucket.count();
+                let mut labels = entry.labels.clone();
+                labels.insert(String::from("le"), format!("{}", unit.normalize(bucket.value() as f64)));
+                line(out, &format!("{}_bucket", name), &labels, cumulative as f64);
+            }
+            let mut inf_labels = entry.labels.clone();
+            inf_labels.insert(String::from("le"), String::from("+Inf"));
+            line(out, &format!("{}_bucket", name), &inf_labels, total as f64);
+            line(out, &format!("{}_sum", name), &entry.labels, sample_sum);
+            line(out, &format!("{}_count", name), &entry.labels, total as f64);
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, metric_type: &str) {
+    out.push_str(&format!("# HELP {} {} reported by rust-metrics\n", name, name));
+    out.push_str(&format!("# TYPE {} {}\n", name, metric_type));
+}
+
+// Emit a single `name{labels} value` sample line with the label values
+// escaped per the exposition format rules.
+fn line(out: &mut String, name: &str, labels: &HashMap<String, String>, value: f64) {
+    if labels.is_empty() {
+        out.push_str(&format!("{} {}\n", name, value));
+    } else {
+        let pairs: Vec<String> = labels.iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+            .collect();
+        out.push_str(&format!("{}{{{}}} {}\n", name, pairs.join(","), value));
+    }
+}
+
+// Escape a label value: backslashes, double quotes and newlines, as required
+// by the text exposition format.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
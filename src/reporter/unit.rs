@@ -0,0 +1,54 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// The unit a recorded metric is measured in. It drives both the canonical
+// Prometheus metric-name suffix and any value normalization needed to emit
+// values in Prometheus base units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Unit {
+    Count,
+    Bytes,
+    Seconds,
+    Milliseconds,
+    Percent,
+}
+
+impl Unit {
+    // The canonical Prometheus unit suffix for this unit, or the empty string
+    // for the dimensionless `Count`. Sub-second units collapse onto `seconds`
+    // because their values are normalized to the base unit before emission.
+    pub fn suffix(&self) -> &'static str {
+        match *self {
+            Unit::Count => "",
+            Unit::Bytes => "bytes",
+            Unit::Seconds | Unit::Milliseconds => "seconds",
+            Unit::Percent => "percent",
+        }
+    }
+
+    // Scale a recorded value into this unit's Prometheus base unit, e.g.
+    // milliseconds become seconds. Units already in base form pass through.
+    pub fn normalize(&self, value: f64) -> f64 {
+        match *self {
+            Unit::Milliseconds => value / 1000.0,
+            _ => value,
+        }
+    }
+}
+
+// Build the fully-qualified Prometheus metric name from the application
+// namespace, the metric's own name and the canonical unit suffix, e.g.
+// `application_request_duration_seconds`. A dimensionless `Count` contributes
+// no suffix. Shared by the push and text-exposition reporters so they always
+// agree on how a name is spelled.
+pub fn metric_name(namespace: &str, name: &str, unit: Unit) -> String {
+    let suffix = unit.suffix();
+    if suffix.is_empty() {
+        format!("{}_{}", namespace, name)
+    } else {
+        format!("{}_{}_{}", namespace, name, suffix)
+    }
+}